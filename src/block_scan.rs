@@ -0,0 +1,209 @@
+use crate::rpc_with_retry::{self, RetryConfig};
+use crate::{decode_compute_budget_instructions, priority_fee_lamports, TransactionData, LAMPORTS_PER_SIGNATURE};
+use chrono::{DateTime, Utc};
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcBlockConfig;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::{TransactionDetails, UiTransactionEncoding};
+use std::str::FromStr;
+
+/// Rough Solana slot rate (slots are targeted at ~400ms). Only used to seed
+/// the binary search with a starting guess - the search itself corrects for
+/// drift between the estimate and reality, as long as the window's start lies
+/// above the search floor (twice the estimate plus 1000 slots back); the
+/// search never returns a slot below that floor.
+const APPROX_SLOTS_PER_SECOND: f64 = 2.5;
+
+/// How far forward to probe when a candidate slot was skipped and therefore
+/// has no block time of its own.
+const SKIPPED_SLOT_PROBE_LIMIT: u64 = 20;
+
+/// Collects fee data by scanning confirmed blocks directly instead of
+/// issuing one `get_transaction` per signature. Determines the slot range
+/// covering `[start_time, current_time]` via a binary search over
+/// `get_block_time`, then walks `get_block` across that range, pulling out
+/// every transaction whose account keys include `sender`.
+pub(crate) fn collect_via_block_scan(
+    client: &RpcClient,
+    sender: &Pubkey,
+    start_time: DateTime<Utc>,
+    current_time: DateTime<Utc>,
+    retry_config: &RetryConfig,
+) -> Result<Vec<TransactionData>, Box<dyn std::error::Error>> {
+    let current_slot = rpc_with_retry::with_retry(retry_config, || client.get_slot())?;
+
+    let seconds_back = (current_time - start_time).num_seconds().max(0) as u64;
+    let estimated_slots_back = (seconds_back as f64 * APPROX_SLOTS_PER_SECOND) as u64;
+    // Give the binary search a generous lower bound in case the endpoint is
+    // producing slots faster than the ~400ms target, which puts the start of
+    // the window further back than the estimate.
+    let search_floor = current_slot.saturating_sub(estimated_slots_back * 2 + 1000);
+
+    let start_slot = find_slot_at_or_after(
+        client,
+        retry_config,
+        start_time.timestamp(),
+        search_floor,
+        current_slot,
+    )?;
+
+    println!(
+        "Block-scan mode: scanning slots {} to {} (~{} slots)",
+        start_slot,
+        current_slot,
+        current_slot.saturating_sub(start_slot) + 1
+    );
+
+    let slots = rpc_with_retry::with_retry(retry_config, || {
+        client.get_blocks(start_slot, Some(current_slot))
+    })?;
+
+    println!("Found {} confirmed slots in range", slots.len());
+
+    let mut transactions_data = Vec::new();
+
+    for (i, slot) in slots.iter().enumerate() {
+        let block = match rpc_with_retry::with_retry(retry_config, || {
+            client.get_block_with_config(
+                *slot,
+                RpcBlockConfig {
+                    encoding: Some(UiTransactionEncoding::Base64),
+                    transaction_details: Some(TransactionDetails::Full),
+                    rewards: Some(false),
+                    commitment: Some(CommitmentConfig::confirmed()),
+                    max_supported_transaction_version: Some(0),
+                },
+            )
+        }) {
+            Ok(block) => block,
+            Err(e) => {
+                println!("Skipping slot {}: {}", slot, e);
+                continue;
+            }
+        };
+
+        let Some(block_time) = block.block_time else {
+            continue;
+        };
+        let timestamp = DateTime::from_timestamp(block_time, 0).expect("Invalid block time");
+        if timestamp < start_time || timestamp > current_time {
+            continue;
+        }
+
+        let Some(block_transactions) = block.transactions else {
+            continue;
+        };
+
+        for tx_with_meta in block_transactions {
+            let Some(decoded_tx) = tx_with_meta.transaction.decode() else {
+                continue;
+            };
+            let Some(meta) = tx_with_meta.meta else {
+                continue;
+            };
+
+            // Static account keys alone miss accounts a v0 transaction pulls
+            // in through an address-lookup table; those are resolved and
+            // reported back on `meta.loaded_addresses`, so check both or a
+            // sender that only appears as a loaded account is silently
+            // dropped here (while the signature-based path would still find
+            // it, since it isn't limited to static keys).
+            let is_sender_involved = decoded_tx.message.static_account_keys().contains(sender)
+                || match &meta.loaded_addresses {
+                    OptionSerializer::Some(loaded) => loaded
+                        .writable
+                        .iter()
+                        .chain(loaded.readonly.iter())
+                        .any(|addr| Pubkey::from_str(addr).as_ref() == Ok(sender)),
+                    OptionSerializer::None | OptionSerializer::Skip => false,
+                };
+            if !is_sender_involved {
+                continue;
+            }
+
+            let fee = meta.fee;
+            let status = meta.status.is_ok();
+            let compute_units: Option<u64> = meta.compute_units_consumed.clone().into();
+
+            let budget_info = decode_compute_budget_instructions(&decoded_tx.message);
+            let num_required_signatures =
+                decoded_tx.message.header().num_required_signatures as u64;
+            let base_fee = LAMPORTS_PER_SIGNATURE * num_required_signatures;
+
+            let compute_unit_limit = budget_info
+                .compute_unit_limit
+                .map(|limit| limit as u64)
+                .or(compute_units);
+            let priority_fee = match (
+                budget_info.compute_unit_price_micro_lamports,
+                compute_unit_limit,
+            ) {
+                (Some(price), Some(limit)) => priority_fee_lamports(price, limit),
+                _ => 0,
+            };
+
+            transactions_data.push(TransactionData {
+                signature: decoded_tx
+                    .signatures
+                    .first()
+                    .map(|sig| sig.to_string())
+                    .unwrap_or_default(),
+                timestamp,
+                success: status,
+                fee_lamports: fee,
+                compute_units,
+                base_fee_lamports: base_fee,
+                priority_fee_lamports: priority_fee,
+                compute_unit_price_micro_lamports: budget_info.compute_unit_price_micro_lamports,
+                compute_unit_limit_used: compute_unit_limit,
+            });
+        }
+
+        if (i + 1) % 50 == 0 || i + 1 == slots.len() {
+            println!("Scanned {}/{} slots", i + 1, slots.len());
+        }
+    }
+
+    Ok(transactions_data)
+}
+
+/// Binary search for the lowest slot in `[low, high]` whose block time is at
+/// or after `target_timestamp`.
+fn find_slot_at_or_after(
+    client: &RpcClient,
+    retry_config: &RetryConfig,
+    target_timestamp: i64,
+    mut low: u64,
+    mut high: u64,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    while low < high {
+        let mid = low + (high - low) / 2;
+
+        match block_time_near(client, retry_config, mid, high)? {
+            Some(block_time) if block_time < target_timestamp => low = mid + 1,
+            _ => high = mid,
+        }
+    }
+
+    Ok(low)
+}
+
+/// `get_block_time` for `slot`, probing forward a handful of slots in case
+/// `slot` itself was skipped and therefore has no block time of its own.
+fn block_time_near(
+    client: &RpcClient,
+    retry_config: &RetryConfig,
+    slot: u64,
+    ceiling: u64,
+) -> Result<Option<i64>, Box<dyn std::error::Error>> {
+    for candidate in slot..=ceiling.min(slot + SKIPPED_SLOT_PROBE_LIMIT) {
+        if let Ok(block_time) =
+            rpc_with_retry::with_retry(retry_config, || client.get_block_time(candidate))
+        {
+            return Ok(Some(block_time));
+        }
+    }
+
+    Ok(None)
+}
@@ -1,24 +1,209 @@
 use chrono::{DateTime, Duration, Timelike, Utc};
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use serde::Serialize;
 use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
 use solana_client::rpc_client::RpcClient;
-use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use solana_sdk::{
+    commitment_config::CommitmentConfig, message::VersionedMessage, pubkey::Pubkey,
+    signature::Signature,
+};
 use solana_transaction_status::UiTransactionEncoding;
 use std::fs::File;
 use std::io::Write;
-use std::thread;
-use std::time::Duration as StdDuration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 use std::{str::FromStr, time::Instant};
 
-#[derive(Debug)]
-struct TransactionData {
-    signature: String,
-    timestamp: DateTime<Utc>,
-    success: bool,
-    fee_lamports: u64,
-    compute_units: Option<u64>,
+mod block_scan;
+mod rate_limiter;
+mod rpc_with_retry;
+use rate_limiter::RateLimiter;
+use rpc_with_retry::RetryConfig;
+
+/// Selects how transaction data is collected for the analysis window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CollectionMode {
+    /// Walk `get_signatures_for_address` then `get_transaction` per
+    /// signature. Works against any RPC endpoint but issues one request
+    /// per transaction.
+    Signatures,
+    /// Binary-search the slot range covering the time window, then scan
+    /// `get_block` for every transaction touching the address. Amortizes
+    /// RPC round-trips across all transactions in a block, but requires an
+    /// endpoint that allows `getBlock`.
+    BlockScan,
 }
 
-#[derive(Debug)]
+impl FromStr for CollectionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "signatures" => Ok(CollectionMode::Signatures),
+            "block-scan" => Ok(CollectionMode::BlockScan),
+            other => Err(format!(
+                "unknown --mode '{}': expected 'signatures' or 'block-scan'",
+                other
+            )),
+        }
+    }
+}
+
+/// Width of the windows `analyze_time_series_data` aggregates transactions
+/// into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BucketSize {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl BucketSize {
+    fn duration(self) -> Duration {
+        match self {
+            BucketSize::Minute => Duration::minutes(1),
+            BucketSize::Hour => Duration::hours(1),
+            BucketSize::Day => Duration::days(1),
+        }
+    }
+
+    /// Rounds `timestamp` down to the start of its bucket.
+    fn truncate(self, timestamp: DateTime<Utc>) -> DateTime<Utc> {
+        let truncated = timestamp
+            .with_nanosecond(0)
+            .unwrap()
+            .with_second(0)
+            .unwrap();
+
+        match self {
+            BucketSize::Minute => truncated,
+            BucketSize::Hour => truncated.with_minute(0).unwrap(),
+            BucketSize::Day => truncated.with_minute(0).unwrap().with_hour(0).unwrap(),
+        }
+    }
+}
+
+impl FromStr for BucketSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "minute" => Ok(BucketSize::Minute),
+            "hour" => Ok(BucketSize::Hour),
+            "day" => Ok(BucketSize::Day),
+            other => Err(format!(
+                "unknown --bucket '{}': expected 'minute', 'hour', or 'day'",
+                other
+            )),
+        }
+    }
+}
+
+/// Output encoding for the per-transaction data file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            other => Err(format!(
+                "unknown --format '{}': expected 'csv' or 'json'",
+                other
+            )),
+        }
+    }
+}
+
+/// Native program that processes `SetComputeUnitLimit` / `SetComputeUnitPrice`
+/// instructions. Transactions opt into priority fees by including an
+/// instruction addressed here before any other instruction runs.
+const COMPUTE_BUDGET_PROGRAM_ID: &str = "ComputeBudget111111111111111111111111111111";
+
+/// Lamports charged per required signature, independent of compute budget.
+pub(crate) const LAMPORTS_PER_SIGNATURE: u64 = 5000;
+
+/// Discriminant (first data byte) for the `SetComputeUnitLimit` instruction.
+const SET_COMPUTE_UNIT_LIMIT_DISCRIMINANT: u8 = 2;
+/// Discriminant (first data byte) for the `SetComputeUnitPrice` instruction.
+const SET_COMPUTE_UNIT_PRICE_DISCRIMINANT: u8 = 3;
+
+/// Compute-budget directives extracted from a transaction's instructions.
+#[derive(Debug, Default)]
+pub(crate) struct ComputeBudgetInfo {
+    pub(crate) compute_unit_price_micro_lamports: Option<u64>,
+    pub(crate) compute_unit_limit: Option<u32>,
+}
+
+/// Scans a transaction's instructions for ComputeBudget directives and
+/// returns the requested CU price and CU limit, if any were set.
+pub(crate) fn decode_compute_budget_instructions(message: &VersionedMessage) -> ComputeBudgetInfo {
+    let compute_budget_program =
+        Pubkey::from_str(COMPUTE_BUDGET_PROGRAM_ID).expect("valid ComputeBudget program id");
+    let account_keys = message.static_account_keys();
+
+    let mut info = ComputeBudgetInfo::default();
+
+    for ix in message.instructions() {
+        let Some(program_id) = account_keys.get(ix.program_id_index as usize) else {
+            continue;
+        };
+        if *program_id != compute_budget_program {
+            continue;
+        }
+
+        match (ix.data.first(), ix.data.len()) {
+            (Some(&SET_COMPUTE_UNIT_LIMIT_DISCRIMINANT), len) if len >= 5 => {
+                info.compute_unit_limit =
+                    Some(u32::from_le_bytes(ix.data[1..5].try_into().unwrap()));
+            }
+            (Some(&SET_COMPUTE_UNIT_PRICE_DISCRIMINANT), len) if len >= 9 => {
+                info.compute_unit_price_micro_lamports =
+                    Some(u64::from_le_bytes(ix.data[1..9].try_into().unwrap()));
+            }
+            _ => {}
+        }
+    }
+
+    info
+}
+
+/// Computes the prioritization fee in lamports for a given CU price and
+/// limit: `ceil(compute_unit_price * compute_unit_limit / 1_000_000)`.
+pub(crate) fn priority_fee_lamports(
+    compute_unit_price_micro_lamports: u64,
+    compute_unit_limit: u64,
+) -> u64 {
+    let micro_lamports = compute_unit_price_micro_lamports.saturating_mul(compute_unit_limit);
+    micro_lamports.saturating_add(999_999) / 1_000_000
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TransactionData {
+    pub(crate) signature: String,
+    pub(crate) timestamp: DateTime<Utc>,
+    pub(crate) success: bool,
+    pub(crate) fee_lamports: u64,
+    pub(crate) compute_units: Option<u64>,
+    pub(crate) base_fee_lamports: u64,
+    pub(crate) priority_fee_lamports: u64,
+    pub(crate) compute_unit_price_micro_lamports: Option<u64>,
+    /// The CU basis `priority_fee_lamports` was actually computed from: the
+    /// explicit `SetComputeUnitLimit` if the transaction set one, else the
+    /// CU actually consumed. Kept alongside `compute_units` (CU consumed)
+    /// so downstream comparisons at a different price use the same basis
+    /// the real fee was charged on instead of silently re-deriving it.
+    pub(crate) compute_unit_limit_used: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
 struct FeeAnalysis {
     total_transactions: usize,
     successful_transactions: usize,
@@ -26,37 +211,185 @@ struct FeeAnalysis {
     total_fees_lamports: u64,
     total_fees_sol: f64,
     average_fee_per_tx: f64,
+    total_base_fees_lamports: u64,
+    total_priority_fees_lamports: u64,
     time_period: TimePeriod,
+    overpayment: Option<OverpaymentReport>,
     transactions: Vec<TransactionData>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 struct TimePeriod {
     from: DateTime<Utc>,
     to: DateTime<Utc>,
 }
 
-async fn calculate_fees(
-    sender_address: &str,
-    hours_to_look_back: i64,
-    rpc_endpoint: &str,
-) -> Result<FeeAnalysis, Box<dyn std::error::Error>> {
-    // Initialize RPC client
-    let client =
-        RpcClient::new_with_commitment(rpc_endpoint.to_string(), CommitmentConfig::confirmed());
+/// Percentiles (in micro-lamports per CU) of the observed
+/// `compute_unit_price_micro_lamports` distribution across the window.
+#[derive(Debug, Serialize)]
+struct PriorityFeePercentiles {
+    p25: u64,
+    p50: u64,
+    p75: u64,
+    p90: u64,
+}
 
-    // Parse sender address
-    let sender = Pubkey::from_str(sender_address)?;
+/// Compares what the wallet actually paid in priority fees against what it
+/// would have paid at each observed percentile, plus a recommended CU price
+/// drawn from a live `getRecentPrioritizationFees` sample. A positive
+/// `overpaid_vs_*` total means the wallet spent more than that percentile
+/// would have implied; negative means it already paid less.
+#[derive(Debug, Serialize)]
+struct OverpaymentReport {
+    observed_percentiles: PriorityFeePercentiles,
+    recommended_compute_unit_price_micro_lamports: Option<u64>,
+    overpaid_vs_p25_lamports: i64,
+    overpaid_vs_p50_lamports: i64,
+    overpaid_vs_p75_lamports: i64,
+    overpaid_vs_p90_lamports: i64,
+}
 
-    println!("Analyzing transactions for address: {}", sender_address);
-    println!("Looking back {} hours from now", hours_to_look_back);
+/// Sums fee totals and success/failure counts from a batch of transactions.
+/// Returns `(total_fees, total_base_fees, total_priority_fees, total, successful, failed)`.
+fn aggregate_transactions(transactions: &[TransactionData]) -> (u64, u64, u64, usize, usize, usize) {
+    let mut total_fees = 0;
+    let mut total_base_fees = 0;
+    let mut total_priority_fees = 0;
+    let mut successful = 0;
+    let mut failed = 0;
+
+    for tx in transactions {
+        total_fees += tx.fee_lamports;
+        total_base_fees += tx.base_fee_lamports;
+        total_priority_fees += tx.priority_fee_lamports;
+        if tx.success {
+            successful += 1;
+        } else {
+            failed += 1;
+        }
+    }
 
-    // Calculate the start time (N hours ago)
-    let current_time = Utc::now();
-    let start_time = current_time - Duration::hours(hours_to_look_back);
+    (
+        total_fees,
+        total_base_fees,
+        total_priority_fees,
+        transactions.len(),
+        successful,
+        failed,
+    )
+}
 
-    println!("Start time: {}", start_time.format("%Y-%m-%d %H:%M:%S"));
+/// Linear-interpolated percentile (0-100) of `sorted_values`, which must
+/// already be sorted ascending.
+fn percentile(sorted_values: &[u64], percentile: f64) -> u64 {
+    if sorted_values.is_empty() {
+        return 0;
+    }
+
+    let rank = (percentile / 100.0) * (sorted_values.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+
+    if lower == upper {
+        sorted_values[lower]
+    } else {
+        let fraction = rank - lower as f64;
+        let interpolated = sorted_values[lower] as f64
+            + (sorted_values[upper] as f64 - sorted_values[lower] as f64) * fraction;
+        interpolated.round() as u64
+    }
+}
+
+/// Builds the observed CU-price percentiles for the window, fetches a live
+/// `getRecentPrioritizationFees` sample for `sender` to derive a recommended
+/// CU price, and sums up how much the wallet overpaid relative to each
+/// percentile. The caller (`calculate_fees`) is responsible for skipping this
+/// entirely when no transaction in the window set a CU price.
+fn build_overpayment_report(
+    client: &RpcClient,
+    sender: &Pubkey,
+    transactions: &[TransactionData],
+    retry_config: &RetryConfig,
+) -> OverpaymentReport {
+    let mut observed_prices: Vec<u64> = transactions
+        .iter()
+        .filter_map(|tx| tx.compute_unit_price_micro_lamports)
+        .collect();
+    observed_prices.sort_unstable();
 
+    let observed_percentiles = PriorityFeePercentiles {
+        p25: percentile(&observed_prices, 25.0),
+        p50: percentile(&observed_prices, 50.0),
+        p75: percentile(&observed_prices, 75.0),
+        p90: percentile(&observed_prices, 90.0),
+    };
+
+    let recommended_compute_unit_price_micro_lamports =
+        match rpc_with_retry::with_retry(retry_config, || {
+            client.get_recent_prioritization_fees(&[*sender])
+        }) {
+            Ok(recent_fees) => {
+                let mut recent_prices: Vec<u64> =
+                    recent_fees.iter().map(|fee| fee.prioritization_fee).collect();
+                recent_prices.sort_unstable();
+                if recent_prices.is_empty() {
+                    None
+                } else {
+                    Some(percentile(&recent_prices, 50.0))
+                }
+            }
+            Err(e) => {
+                println!("Could not fetch recent prioritization fees: {}", e);
+                None
+            }
+        };
+
+    // Sums `actual priority fee - implied priority fee at `price`` across
+    // every transaction that actually set a CU price (the same filter as
+    // `observed_prices` above), so transactions that never bid on priority
+    // fees don't drag the total down with a spurious negative term. Uses
+    // `compute_unit_limit_used`, the same CU basis `priority_fee_lamports`
+    // was actually charged on, rather than `compute_units` (CU consumed) -
+    // wallets commonly request headroom above what they end up using, so
+    // comparing against consumed CU would price the two fees on different
+    // bases.
+    let overpaid_vs = |price: u64| -> i64 {
+        transactions
+            .iter()
+            .filter_map(|tx| {
+                tx.compute_unit_price_micro_lamports?;
+                let compute_unit_limit = tx.compute_unit_limit_used?;
+                let implied_fee = priority_fee_lamports(price, compute_unit_limit);
+                Some(tx.priority_fee_lamports as i64 - implied_fee as i64)
+            })
+            .sum()
+    };
+
+    OverpaymentReport {
+        overpaid_vs_p25_lamports: overpaid_vs(observed_percentiles.p25),
+        overpaid_vs_p50_lamports: overpaid_vs(observed_percentiles.p50),
+        overpaid_vs_p75_lamports: overpaid_vs(observed_percentiles.p75),
+        overpaid_vs_p90_lamports: overpaid_vs(observed_percentiles.p90),
+        observed_percentiles,
+        recommended_compute_unit_price_micro_lamports,
+    }
+}
+
+/// Collects transaction data the default way: list signatures for the
+/// address via `get_signatures_for_address`, then fetch each transaction's
+/// details with `get_transaction`, up to `concurrency` requests in flight at
+/// once. `rate_limiter` is the actual requests-per-second guard: the worker
+/// pool only bounds how many requests are in flight, which by itself says
+/// nothing about the endpoint's RPS budget.
+fn collect_via_signatures(
+    client: &RpcClient,
+    sender: &Pubkey,
+    start_time: DateTime<Utc>,
+    current_time: DateTime<Utc>,
+    retry_config: &RetryConfig,
+    concurrency: usize,
+    rate_limiter: &RateLimiter,
+) -> Result<Vec<TransactionData>, Box<dyn std::error::Error>> {
     // Get signatures for the address
     let mut all_signatures = Vec::new();
     let mut before: Option<Signature> = None;
@@ -64,15 +397,18 @@ async fn calculate_fees(
 
     loop {
         // Get batch of signatures
-        let signatures = client.get_signatures_for_address_with_config(
-            &sender,
-            GetConfirmedSignaturesForAddress2Config {
-                before,
-                limit: Some(limit),
-                until: None,
-                commitment: Some(CommitmentConfig::confirmed()),
-            },
-        )?;
+        let signatures = rpc_with_retry::with_retry(retry_config, || {
+            rate_limiter.acquire();
+            client.get_signatures_for_address_with_config(
+                sender,
+                GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    limit: Some(limit),
+                    until: None,
+                    commitment: Some(CommitmentConfig::confirmed()),
+                },
+            )
+        })?;
 
         if signatures.is_empty() {
             break;
@@ -107,9 +443,6 @@ async fn calculate_fees(
         if let Some(oldest_sig) = signatures.last() {
             before = Some(Signature::from_str(&oldest_sig.signature)?);
         }
-
-        // Small delay to avoid rate limiting
-        thread::sleep(StdDuration::from_millis(100));
     }
 
     println!("Retrieved {} total signatures", all_signatures.len());
@@ -131,47 +464,35 @@ async fn calculate_fees(
         filtered_signatures.len()
     );
 
-    // Get transaction details and calculate fees
-    let mut total_fees: u64 = 0;
-    let mut processed_tx_count = 0;
-    let mut successful_tx_count = 0;
-    let mut failed_tx_count = 0;
-    let mut transactions_data = Vec::new();
-
-    // Process in smaller batches to avoid rate limiting
-    let batch_size = 5;
-    let signature_chunks: Vec<_> = filtered_signatures
-        .chunks(batch_size)
-        .map(|chunk| chunk.to_vec())
-        .collect();
-
-    let timer = Instant::now();
-
-    for (i, chunk) in signature_chunks.iter().enumerate() {
-        // Process each signature in the chunk
-        let mut chunk_fees = 0;
-        let mut chunk_count = 0;
-
-        for sig_info in chunk {
-            let sig = Signature::from_str(&sig_info.signature)?;
+    // Fetch transaction details on a bounded worker pool.
+    let processed_tx_count = AtomicUsize::new(0);
+    let transactions_data: Mutex<Vec<TransactionData>> = Mutex::new(Vec::new());
+    let pool = ThreadPoolBuilder::new().num_threads(concurrency).build()?;
+    let total_to_fetch = filtered_signatures.len();
 
-            // Get transaction details
-            match client.get_transaction(&sig, UiTransactionEncoding::Json) {
+    pool.install(|| {
+        filtered_signatures.par_iter().for_each(|sig_info| {
+            let sig = match Signature::from_str(&sig_info.signature) {
+                Ok(sig) => sig,
+                Err(e) => {
+                    println!("Invalid signature {}: {}", sig_info.signature, e);
+                    return;
+                }
+            };
+
+            // Get transaction details. Base64 encoding is requested (rather
+            // than Json) so the raw message bytes are available for decoding
+            // ComputeBudget instructions below.
+            match rpc_with_retry::with_retry(retry_config, || {
+                rate_limiter.acquire();
+                client.get_transaction(&sig, UiTransactionEncoding::Base64)
+            }) {
                 Ok(tx) => {
                     if let Some(meta) = tx.transaction.meta {
                         let fee = meta.fee;
-                        total_fees += fee;
-                        chunk_fees += fee;
-                        processed_tx_count += 1;
-                        chunk_count += 1;
 
                         // Check transaction status
                         let status = meta.status.is_ok();
-                        if status {
-                            successful_tx_count += 1;
-                        } else {
-                            failed_tx_count += 1;
-                        }
 
                         // Get timestamp
                         let block_time = tx.block_time.unwrap_or(0);
@@ -181,30 +502,69 @@ async fn calculate_fees(
                         // Convert compute units
                         let compute_units: Option<u64> = meta.compute_units_consumed.clone().into();
 
+                        // Decode the ComputeBudget instructions (if any) to split the
+                        // fee into its base and prioritization components.
+                        let decoded_tx = tx.transaction.transaction.decode();
+                        let budget_info = decoded_tx
+                            .as_ref()
+                            .map(|vtx| decode_compute_budget_instructions(&vtx.message))
+                            .unwrap_or_default();
+
+                        let num_required_signatures = decoded_tx
+                            .as_ref()
+                            .map(|vtx| vtx.message.header().num_required_signatures as u64)
+                            .unwrap_or(1);
+                        let base_fee = LAMPORTS_PER_SIGNATURE * num_required_signatures;
+
+                        let compute_unit_limit = budget_info
+                            .compute_unit_limit
+                            .map(|limit| limit as u64)
+                            .or(compute_units);
+                        let priority_fee = match (
+                            budget_info.compute_unit_price_micro_lamports,
+                            compute_unit_limit,
+                        ) {
+                            (Some(price), Some(limit)) => priority_fee_lamports(price, limit),
+                            _ => 0,
+                        };
+
+                        let processed = processed_tx_count.fetch_add(1, Ordering::Relaxed) + 1;
+
                         // Store transaction data
-                        transactions_data.push(TransactionData {
+                        transactions_data.lock().unwrap().push(TransactionData {
                             signature: sig_info.signature.clone(),
                             timestamp,
                             success: status,
                             fee_lamports: fee,
                             compute_units,
+                            base_fee_lamports: base_fee,
+                            priority_fee_lamports: priority_fee,
+                            compute_unit_price_micro_lamports: budget_info
+                                .compute_unit_price_micro_lamports,
+                            compute_unit_limit_used: compute_unit_limit,
                         });
 
                         // Optional: Log compute units if available
                         if let Some(cu) = compute_units {
                             println!(
-                                "Transaction {}: {} lamports, {} compute units, success: {}, time: {}",
-                                processed_tx_count,
+                                "Transaction {}/{}: {} lamports ({} base, {} priority), {} compute units, success: {}, time: {}",
+                                processed,
+                                total_to_fetch,
                                 fee,
+                                base_fee,
+                                priority_fee,
                                 cu,
                                 status,
                                 timestamp.format("%Y-%m-%d %H:%M:%S")
                             );
                         } else {
                             println!(
-                                "Transaction {}: {} lamports, success: {}, time: {}",
-                                processed_tx_count,
+                                "Transaction {}/{}: {} lamports ({} base, {} priority), success: {}, time: {}",
+                                processed,
+                                total_to_fetch,
                                 fee,
+                                base_fee,
+                                priority_fee,
                                 status,
                                 timestamp.format("%Y-%m-%d %H:%M:%S")
                             );
@@ -215,30 +575,66 @@ async fn calculate_fees(
                     println!("Error fetching transaction {}: {}", sig, e);
                 }
             }
+        });
+    });
 
-            // Small delay between transactions in a batch
-            thread::sleep(StdDuration::from_millis(100));
-        }
+    Ok(transactions_data.into_inner().unwrap())
+}
 
-        // Progress indicator
-        println!(
-            "Batch {}/{}: Processed {} transactions, {} lamports fees",
-            i + 1,
-            signature_chunks.len(),
-            chunk_count,
-            chunk_fees
-        );
+async fn calculate_fees(
+    sender_address: &str,
+    hours_to_look_back: i64,
+    rpc_endpoint: &str,
+    max_retries: u32,
+    concurrency: usize,
+    max_requests_per_second: u32,
+    mode: CollectionMode,
+) -> Result<FeeAnalysis, Box<dyn std::error::Error>> {
+    // Initialize RPC client
+    let client =
+        RpcClient::new_with_commitment(rpc_endpoint.to_string(), CommitmentConfig::confirmed());
 
-        println!(
-            "Total progress: {}/{} transactions ({}%)",
-            processed_tx_count,
-            filtered_signatures.len(),
-            (processed_tx_count as f64 / filtered_signatures.len() as f64 * 100.0).round()
-        );
+    let retry_config = RetryConfig {
+        max_retries,
+        ..RetryConfig::default()
+    };
+    let rate_limiter = RateLimiter::new(max_requests_per_second);
 
-        // Larger delay between batches
-        thread::sleep(StdDuration::from_millis(500));
-    }
+    // Parse sender address
+    let sender = Pubkey::from_str(sender_address)?;
+
+    println!("Analyzing transactions for address: {}", sender_address);
+    println!("Looking back {} hours from now", hours_to_look_back);
+    println!("Collection mode: {:?}", mode);
+
+    // Calculate the start time (N hours ago)
+    let current_time = Utc::now();
+    let start_time = current_time - Duration::hours(hours_to_look_back);
+
+    println!("Start time: {}", start_time.format("%Y-%m-%d %H:%M:%S"));
+
+    let timer = Instant::now();
+
+    let mut transactions_data = match mode {
+        CollectionMode::Signatures => collect_via_signatures(
+            &client,
+            &sender,
+            start_time,
+            current_time,
+            &retry_config,
+            concurrency,
+            &rate_limiter,
+        )?,
+        CollectionMode::BlockScan => {
+            block_scan::collect_via_block_scan(&client, &sender, start_time, current_time, &retry_config)?
+        }
+    };
+
+    // Sort transactions by timestamp
+    transactions_data.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+
+    let (total_fees, total_base_fees, total_priority_fees, processed_tx_count, successful_tx_count, failed_tx_count) =
+        aggregate_transactions(&transactions_data);
 
     // Convert lamports to SOL for final output
     let total_fees_in_sol = total_fees as f64 / 1_000_000_000.0;
@@ -264,6 +660,10 @@ async fn calculate_fees(
         "Total fees spent: {} lamports ({:.9} SOL)",
         total_fees, total_fees_in_sol
     );
+    println!(
+        "  of which base fees: {} lamports, priority fees: {} lamports",
+        total_base_fees, total_priority_fees
+    );
     println!("Average fee per transaction: {:.2} lamports", average_fee);
     println!(
         "Time period: {} to {}",
@@ -272,8 +672,34 @@ async fn calculate_fees(
     );
     println!("Analysis completed in {:.2?}", timer.elapsed());
 
-    // Sort transactions by timestamp
-    transactions_data.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    let overpayment = if transactions_data
+        .iter()
+        .any(|tx| tx.compute_unit_price_micro_lamports.is_some())
+    {
+        let report = build_overpayment_report(&client, &sender, &transactions_data, &retry_config);
+        println!("\n--- OVERPAYMENT REPORT ---");
+        println!(
+            "Observed CU price percentiles (micro-lamports): p25={} p50={} p75={} p90={}",
+            report.observed_percentiles.p25,
+            report.observed_percentiles.p50,
+            report.observed_percentiles.p75,
+            report.observed_percentiles.p90
+        );
+        match report.recommended_compute_unit_price_micro_lamports {
+            Some(price) => println!("Recommended CU price (recent network activity): {} micro-lamports", price),
+            None => println!("Recommended CU price: unavailable (no recent prioritization fee data)"),
+        }
+        println!(
+            "Overpaid vs p25: {} lamports, vs p50: {} lamports, vs p75: {} lamports, vs p90: {} lamports",
+            report.overpaid_vs_p25_lamports,
+            report.overpaid_vs_p50_lamports,
+            report.overpaid_vs_p75_lamports,
+            report.overpaid_vs_p90_lamports
+        );
+        Some(report)
+    } else {
+        None
+    };
 
     Ok(FeeAnalysis {
         total_transactions: processed_tx_count,
@@ -282,22 +708,77 @@ async fn calculate_fees(
         total_fees_lamports: total_fees,
         total_fees_sol: total_fees_in_sol,
         average_fee_per_tx: average_fee,
+        total_base_fees_lamports: total_base_fees,
+        total_priority_fees_lamports: total_priority_fees,
         time_period: TimePeriod {
             from: start_time,
             to: current_time,
         },
+        overpayment,
         transactions: transactions_data,
     })
 }
 
+/// Default number of `get_transaction` requests kept in flight at once.
+const DEFAULT_CONCURRENCY: usize = 5;
+
+/// Default ceiling on requests per second against the RPC endpoint,
+/// independent of `--concurrency`. Conservative enough for public endpoints.
+/// Only the signature-based collection path is throttled; block-scan mode
+/// and the `getRecentPrioritizationFees` lookup do not go through the limiter.
+const DEFAULT_MAX_RPS: u32 = 10;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Command line arguments (or replace with your values)
-    let args: Vec<String> = std::env::args().collect();
+    // Command line arguments (or replace with your values). `--concurrency`,
+    // `--max-rps`, `--mode`, `--bucket` and `--format` are accepted anywhere
+    // on the command line; everything else is positional, in the order shown
+    // in the usage string below.
+    let raw_args: Vec<String> = std::env::args().collect();
+    let mut args = Vec::new();
+    let mut concurrency = DEFAULT_CONCURRENCY;
+    let mut max_rps = DEFAULT_MAX_RPS;
+    let mut mode = CollectionMode::Signatures;
+    let mut bucket = BucketSize::Hour;
+    let mut format = OutputFormat::Csv;
+
+    let mut iter = raw_args.iter();
+    while let Some(arg) = iter.next() {
+        if let Some(value) = arg.strip_prefix("--concurrency=") {
+            concurrency = value.parse()?;
+        } else if arg == "--concurrency" {
+            let value = iter
+                .next()
+                .ok_or("--concurrency requires a value")?;
+            concurrency = value.parse()?;
+        } else if let Some(value) = arg.strip_prefix("--max-rps=") {
+            max_rps = value.parse()?;
+        } else if arg == "--max-rps" {
+            let value = iter.next().ok_or("--max-rps requires a value")?;
+            max_rps = value.parse()?;
+        } else if let Some(value) = arg.strip_prefix("--mode=") {
+            mode = value.parse()?;
+        } else if arg == "--mode" {
+            let value = iter.next().ok_or("--mode requires a value")?;
+            mode = value.parse()?;
+        } else if let Some(value) = arg.strip_prefix("--bucket=") {
+            bucket = value.parse()?;
+        } else if arg == "--bucket" {
+            let value = iter.next().ok_or("--bucket requires a value")?;
+            bucket = value.parse()?;
+        } else if let Some(value) = arg.strip_prefix("--format=") {
+            format = value.parse()?;
+        } else if arg == "--format" {
+            let value = iter.next().ok_or("--format requires a value")?;
+            format = value.parse()?;
+        } else {
+            args.push(arg.clone());
+        }
+    }
 
     if args.len() < 3 {
         println!(
-            "Usage: {} <WALLET_ADDRESS> <HOURS_TO_LOOK_BACK> [RPC_ENDPOINT]",
+            "Usage: {} <WALLET_ADDRESS> <HOURS_TO_LOOK_BACK> [RPC_ENDPOINT] [MAX_RETRIES] [--concurrency N] [--max-rps N] [--mode signatures|block-scan] [--bucket minute|hour|day] [--format csv|json]",
             args[0]
         );
         println!(
@@ -314,10 +795,27 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     } else {
         "https://api.mainnet-beta.solana.com"
     };
+    let max_retries: u32 = if args.len() > 4 {
+        args[4].parse()?
+    } else {
+        RetryConfig::default().max_retries
+    };
 
     println!("Starting analysis for wallet: {}", wallet_address);
-
-    match calculate_fees(wallet_address, hours, rpc_endpoint).await {
+    println!("Concurrency: {} in-flight requests", concurrency);
+    println!("Rate limit: {} requests/sec", max_rps);
+
+    match calculate_fees(
+        wallet_address,
+        hours,
+        rpc_endpoint,
+        max_retries,
+        concurrency,
+        max_rps,
+        mode,
+    )
+    .await
+    {
         Ok(analysis) => {
             println!("\nAnalysis complete!");
             println!("Total transactions: {}", analysis.total_transactions);
@@ -346,21 +844,42 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 analysis.time_period.to.format("%Y-%m-%d %H:%M:%S")
             );
             println!("Total fees in lamports: {}", analysis.total_fees_lamports);
+            println!(
+                "  of which base fees: {} lamports, priority fees: {} lamports",
+                analysis.total_base_fees_lamports, analysis.total_priority_fees_lamports
+            );
 
-            // Generate timestamped CSV file with transaction data
+            // Generate timestamped transaction data file in the requested format
             let timestamp = Utc::now().format("%Y%m%d_%H%M%S");
-            let file_name = format!("tx_data_{}_{}.csv", wallet_address, timestamp);
-
-            match save_transaction_data(&analysis, &file_name) {
+            let extension = match format {
+                OutputFormat::Csv => "csv",
+                OutputFormat::Json => "json",
+            };
+            let file_name = format!("tx_data_{}_{}.{}", wallet_address, timestamp, extension);
+
+            let save_result = match format {
+                OutputFormat::Csv => save_transaction_data(&analysis, &file_name),
+                OutputFormat::Json => save_transaction_data_json(&analysis, &file_name),
+            };
+            match save_result {
                 Ok(_) => println!("Transaction data saved to {}", file_name),
                 Err(e) => eprintln!("Error saving transaction data: {}", e),
             }
 
             // Generate time series analysis
-            match analyze_time_series_data(&analysis) {
+            match analyze_time_series_data(&analysis, bucket) {
                 Ok(output_file) => println!("Time series analysis saved to {}", output_file),
                 Err(e) => eprintln!("Error generating time series analysis: {}", e),
             }
+
+            // Generate the overpayment report, if one was computed
+            if let Some(report) = &analysis.overpayment {
+                let report_file = format!("overpayment_report_{}_{}.csv", wallet_address, timestamp);
+                match save_overpayment_report(report, &report_file) {
+                    Ok(_) => println!("Overpayment report saved to {}", report_file),
+                    Err(e) => eprintln!("Error saving overpayment report: {}", e),
+                }
+            }
         }
         Err(e) => {
             eprintln!("Error during analysis: {}", e);
@@ -370,160 +889,146 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// Writes one quoted CSV record per transaction via the `csv` crate. Unlike
+/// the old hand-rolled writer, this survives fields that contain commas and
+/// doesn't mix tabular rows with free-text summary lines in the same file -
+/// summary statistics belong in the printed report, not the machine-readable
+/// output.
 fn save_transaction_data(
     analysis: &FeeAnalysis,
     file_path: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut file = File::create(file_path)?;
-
-    // Write CSV header
-    writeln!(
-        file,
-        "timestamp,signature,success,fee_lamports,compute_units"
-    )?;
+    let mut writer = csv::Writer::from_path(file_path)?;
 
-    // Write transaction data
     for tx in &analysis.transactions {
-        let compute_units_str = match tx.compute_units {
-            Some(cu) => cu.to_string(),
-            None => "N/A".to_string(),
-        };
-
-        writeln!(
-            file,
-            "{},{},{},{},{}",
-            tx.timestamp.format("%Y-%m-%d %H:%M:%S"),
-            tx.signature,
-            tx.success,
-            tx.fee_lamports,
-            compute_units_str
-        )?;
+        writer.serialize(tx)?;
     }
 
-    // Write summary statistics
-    writeln!(file, "\nSUMMARY STATISTICS")?;
-    writeln!(
-        file,
-        "Time period,{} to {}",
-        analysis.time_period.from.format("%Y-%m-%d %H:%M:%S"),
-        analysis.time_period.to.format("%Y-%m-%d %H:%M:%S")
-    )?;
-    writeln!(file, "Total transactions,{}", analysis.total_transactions)?;
-    writeln!(
-        file,
-        "Successful transactions,{}",
-        analysis.successful_transactions
-    )?;
-    writeln!(file, "Failed transactions,{}", analysis.failed_transactions)?;
-    writeln!(
-        file,
-        "Success rate,%{:.2}",
-        if analysis.total_transactions > 0 {
-            (analysis.successful_transactions as f64 / analysis.total_transactions as f64) * 100.0
-        } else {
-            0.0
-        }
-    )?;
-    writeln!(file, "Total fees (SOL),{:.9}", analysis.total_fees_sol)?;
-    writeln!(
-        file,
-        "Total fees (lamports),{}",
-        analysis.total_fees_lamports
-    )?;
-    writeln!(
-        file,
-        "Average fee per transaction (lamports),{:.2}",
-        analysis.average_fee_per_tx
-    )?;
+    writer.flush()?;
+    Ok(())
+}
 
+/// Serializes the full `FeeAnalysis` (summary stats plus every transaction)
+/// as pretty-printed JSON.
+fn save_transaction_data_json(
+    analysis: &FeeAnalysis,
+    file_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file = File::create(file_path)?;
+    serde_json::to_writer_pretty(file, analysis)?;
     Ok(())
 }
 
-fn analyze_time_series_data(analysis: &FeeAnalysis) -> Result<String, Box<dyn std::error::Error>> {
-    // Create a timestamp for the output file
-    let timestamp = Utc::now().format("%Y%m%d_%H%M%S");
-    let output_file = format!("time_series_analysis_{}.txt", timestamp);
-    let mut file = File::create(&output_file)?;
+/// Flattened, single-row CSV view of an [`OverpaymentReport`] - the `csv`
+/// crate serializes flat structs, not the nested one used for JSON output.
+#[derive(Debug, Serialize)]
+struct OverpaymentReportRow {
+    observed_p25_micro_lamports: u64,
+    observed_p50_micro_lamports: u64,
+    observed_p75_micro_lamports: u64,
+    observed_p90_micro_lamports: u64,
+    recommended_compute_unit_price_micro_lamports: Option<u64>,
+    overpaid_vs_p25_lamports: i64,
+    overpaid_vs_p50_lamports: i64,
+    overpaid_vs_p75_lamports: i64,
+    overpaid_vs_p90_lamports: i64,
+}
 
-    // Group transactions by hour
-    let mut hourly_data: Vec<(DateTime<Utc>, usize, usize)> = Vec::new();
+/// Writes the overpayment report as a single-row CSV, mirroring how
+/// `save_transaction_data` writes one row per transaction.
+fn save_overpayment_report(
+    report: &OverpaymentReport,
+    file_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = csv::Writer::from_path(file_path)?;
+
+    writer.serialize(OverpaymentReportRow {
+        observed_p25_micro_lamports: report.observed_percentiles.p25,
+        observed_p50_micro_lamports: report.observed_percentiles.p50,
+        observed_p75_micro_lamports: report.observed_percentiles.p75,
+        observed_p90_micro_lamports: report.observed_percentiles.p90,
+        recommended_compute_unit_price_micro_lamports: report
+            .recommended_compute_unit_price_micro_lamports,
+        overpaid_vs_p25_lamports: report.overpaid_vs_p25_lamports,
+        overpaid_vs_p50_lamports: report.overpaid_vs_p50_lamports,
+        overpaid_vs_p75_lamports: report.overpaid_vs_p75_lamports,
+        overpaid_vs_p90_lamports: report.overpaid_vs_p90_lamports,
+    })?;
+
+    writer.flush()?;
+    Ok(())
+}
 
-    if !analysis.transactions.is_empty() {
-        // Start with the first transaction's hour
-        let mut current_hour = analysis.transactions[0]
-            .timestamp
-            .with_minute(0)
-            .unwrap()
-            .with_second(0)
-            .unwrap()
-            .with_nanosecond(0)
-            .unwrap();
+/// One row of the time-series CSV: aggregated stats for all transactions
+/// falling in `[bucket_start, bucket_start + bucket size)`.
+#[derive(Debug, Serialize)]
+struct TimeSeriesBucket {
+    bucket_start: DateTime<Utc>,
+    count: usize,
+    successful: usize,
+    success_rate: f64,
+    total_fees_lamports: u64,
+    average_priority_fee_lamports: f64,
+}
 
-        let end_time = analysis
-            .time_period
-            .to
-            .with_minute(0)
-            .unwrap()
-            .with_second(0)
-            .unwrap()
-            .with_nanosecond(0)
-            .unwrap()
-            + Duration::hours(1); // Include the last hour
+/// Buckets `analysis.transactions` into fixed-size windows and writes one
+/// CSV row per window with count, success rate, total fees, and average
+/// priority fee - feeding directly into downstream plotting or analysis
+/// tooling instead of a hand-formatted report.
+fn analyze_time_series_data(
+    analysis: &FeeAnalysis,
+    bucket_size: BucketSize,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let timestamp = Utc::now().format("%Y%m%d_%H%M%S");
+    let output_file = format!("time_series_analysis_{}.csv", timestamp);
+    let mut writer = csv::Writer::from_path(&output_file)?;
+
+    if !analysis.transactions.is_empty() {
+        let bucket_duration = bucket_size.duration();
+        let mut current_bucket = bucket_size.truncate(analysis.transactions[0].timestamp);
+        let end_bucket = bucket_size.truncate(analysis.time_period.to) + bucket_duration;
 
-        while current_hour <= end_time {
-            let next_hour = current_hour + Duration::hours(1);
+        while current_bucket <= end_bucket {
+            let next_bucket = current_bucket + bucket_duration;
 
-            // Count transactions in this hour
-            let transactions_in_hour: Vec<_> = analysis
+            let transactions_in_bucket: Vec<_> = analysis
                 .transactions
                 .iter()
-                .filter(|tx| tx.timestamp >= current_hour && tx.timestamp < next_hour)
+                .filter(|tx| tx.timestamp >= current_bucket && tx.timestamp < next_bucket)
                 .collect();
 
-            let total = transactions_in_hour.len();
-            let successful = transactions_in_hour.iter().filter(|tx| tx.success).count();
-
-            hourly_data.push((current_hour, successful, total));
-
-            current_hour = next_hour;
+            let count = transactions_in_bucket.len();
+            let successful = transactions_in_bucket.iter().filter(|tx| tx.success).count();
+            let success_rate = if count > 0 {
+                (successful as f64 / count as f64) * 100.0
+            } else {
+                0.0
+            };
+            let total_fees_lamports: u64 =
+                transactions_in_bucket.iter().map(|tx| tx.fee_lamports).sum();
+            let average_priority_fee_lamports = if count > 0 {
+                transactions_in_bucket
+                    .iter()
+                    .map(|tx| tx.priority_fee_lamports)
+                    .sum::<u64>() as f64
+                    / count as f64
+            } else {
+                0.0
+            };
+
+            writer.serialize(TimeSeriesBucket {
+                bucket_start: current_bucket,
+                count,
+                successful,
+                success_rate,
+                total_fees_lamports,
+                average_priority_fee_lamports,
+            })?;
+
+            current_bucket = next_bucket;
         }
     }
 
-    // Write hourly data to file
-    writeln!(file, "TIME SERIES ANALYSIS BY HOUR")?;
-    writeln!(file, "hour,successful,total,success_rate")?;
-
-    for (hour, successful, total) in &hourly_data {
-        let success_rate = if *total > 0 {
-            (*successful as f64 / *total as f64) * 100.0
-        } else {
-            0.0
-        };
-
-        writeln!(
-            file,
-            "{},{},{},{:.2}%",
-            hour.format("%Y-%m-%d %H:00"),
-            successful,
-            total,
-            success_rate
-        )?;
-    }
-
-    // Write instructions for plotting
-    writeln!(file, "\nTo visualize this data with any plotting tool:")?;
-    writeln!(file, "1. The CSV data above can be imported into Excel, Google Sheets, or any data analysis tool")?;
-    writeln!(file, "2. Create a line chart with:")?;
-    writeln!(file, "   - X-axis: hour")?;
-    writeln!(file, "   - Y-axis: success_rate")?;
-    writeln!(
-        file,
-        "3. This will show how the transaction success rate changes over time"
-    )?;
-    writeln!(
-        file,
-        "\nAlternatively, use a tool like Python with matplotlib or R for more advanced analysis."
-    )?;
-
+    writer.flush()?;
     Ok(output_file)
 }
@@ -0,0 +1,51 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Enforces a global requests-per-second ceiling shared by every caller,
+/// independent of how many worker threads are fetching concurrently. A
+/// bounded thread pool only caps how many requests are *in flight*; with
+/// fast-returning requests that still lets N always-busy workers blow past
+/// an endpoint's RPS budget, so this is the actual guard the worker pool
+/// sits behind.
+pub struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// `max_requests_per_second == 0` disables throttling entirely.
+    pub fn new(max_requests_per_second: u32) -> Self {
+        let interval = if max_requests_per_second == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(1.0 / max_requests_per_second as f64)
+        };
+
+        Self {
+            interval,
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Blocks the calling thread until the next request slot is free, then
+    /// reserves it. Safe to call from multiple threads at once: slots are
+    /// handed out in the order callers arrive.
+    pub fn acquire(&self) {
+        if self.interval.is_zero() {
+            return;
+        }
+
+        let wait = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let now = Instant::now();
+            let slot = (*next_slot).max(now);
+            *next_slot = slot + self.interval;
+            slot.saturating_duration_since(now)
+        };
+
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
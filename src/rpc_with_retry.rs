@@ -0,0 +1,115 @@
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::client_error::Result as ClientResult;
+use solana_client::rpc_request::RpcError;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Tuning knobs for [`with_retry`]. `max_retries` is the number of *extra*
+/// attempts made after the first one fails, so a call can run at most
+/// `max_retries + 1` times.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryConfig {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Runs `operation`, retrying with exponential backoff (plus jitter) while
+/// the error it returns looks transient. Permanent errors (bad signature,
+/// invalid pubkey, malformed request, ...) are returned immediately so we
+/// don't waste retries on something that will never succeed.
+pub fn with_retry<T, F>(config: &RetryConfig, mut operation: F) -> ClientResult<T>
+where
+    F: FnMut() -> ClientResult<T>,
+{
+    let mut attempt = 0;
+
+    loop {
+        match operation() {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < config.max_retries && is_retryable(&err) => {
+                let delay = backoff_delay(config, attempt);
+                eprintln!(
+                    "Retryable RPC error (attempt {}/{}): {} - retrying in {:?}",
+                    attempt + 1,
+                    config.max_retries,
+                    err,
+                    delay
+                );
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// `base_delay * 2^attempt`, capped at `max_delay`, plus up to `base_delay`
+/// of jitter so a batch of concurrent retries doesn't all wake up in lockstep.
+fn backoff_delay(config: &RetryConfig, attempt: u32) -> Duration {
+    let exponential = config
+        .base_delay
+        .saturating_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX));
+    let capped = exponential.min(config.max_delay);
+    capped + Duration::from_millis(jitter_ms(config.base_delay.as_millis() as u64))
+}
+
+/// A small amount of jitter derived from the current time, avoiding a hard
+/// dependency on a `rand` crate for what is otherwise a single-purpose tool.
+fn jitter_ms(max_jitter_ms: u64) -> u64 {
+    if max_jitter_ms == 0 {
+        return 0;
+    }
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or(0);
+
+    nanos % max_jitter_ms
+}
+
+/// Whether retrying `err` has a reasonable chance of succeeding: rate limits,
+/// timeouts, and nodes that are behind are worth another attempt, while
+/// malformed requests and other client-side mistakes are not.
+fn is_retryable(err: &ClientError) -> bool {
+    match err.kind() {
+        ClientErrorKind::Io(_) => true,
+        ClientErrorKind::Reqwest(_) => true,
+        ClientErrorKind::RpcError(rpc_err) => is_retryable_rpc_error(rpc_err),
+        _ => false,
+    }
+}
+
+fn is_retryable_rpc_error(err: &RpcError) -> bool {
+    match err {
+        // Transport-level request failures (connection reset, timed out
+        // before a response arrived, ...) are always worth another attempt.
+        RpcError::RpcRequestError(_) => true,
+        RpcError::RpcResponseError { code, message, .. } => {
+            let message = message.to_lowercase();
+            // -32005: node is behind / unable to serve the request right now.
+            *code == -32005
+                || message.contains("429")
+                || message.contains("rate limit")
+                || message.contains("too many requests")
+                || message.contains("timed out")
+                || message.contains("timeout")
+                || message.contains("node is behind")
+        }
+        // The server understood the request but it was malformed, or we
+        // couldn't even parse the response - retrying won't help either.
+        RpcError::ParseError(_) => false,
+        RpcError::ForUser(_) => false,
+    }
+}